@@ -1,15 +1,21 @@
 //! A pass that eliminates branches on uninhabited enum variants.
 
 use crate::transform::{MirPass, MirSource};
-use rustc::mir::{BasicBlock, Body, Local, Operand, Rvalue, StatementKind, TerminatorKind};
+use rustc::mir::{
+    BasicBlock, BasicBlockData, Body, Local, Operand, Rvalue, StatementKind, Terminator,
+    TerminatorKind,
+};
 use rustc::ty::layout::{Abi, TyLayout, Variants};
 use rustc::ty::{Ty, TyCtxt};
 
 pub struct UninhabitedEnumBranching;
 
 fn get_discriminant_local(terminator: &TerminatorKind<'_>) -> Option<Local> {
-    if let TerminatorKind::SwitchInt { discr: Operand::Move(p), .. } = terminator {
-        p.as_local()
+    if let TerminatorKind::SwitchInt { discr, .. } = terminator {
+        match discr {
+            Operand::Move(p) | Operand::Copy(p) => p.as_local(),
+            Operand::Constant(_) => None,
+        }
     } else {
         None
     }
@@ -21,24 +27,55 @@ fn find_eligible_blocks<'tcx>(body: &Body<'tcx>) -> Vec<(BasicBlock, Ty<'tcx>)>
     let mut blocks_to_update = Vec::new();
 
     for (bb, block_data) in body.basic_blocks().iter_enumerated() {
+        // Rerouting dead edges to a freshly synthesized (non-cleanup) `Unreachable` block would
+        // create an illegal cleanup -> non-cleanup edge, so leave cleanup blocks alone.
+        if block_data.is_cleanup {
+            continue;
+        }
+
         let terminator = block_data.terminator();
 
         // Only bother checking blocks which terminate by switching on a local.
         if let Some(local) = get_discriminant_local(&terminator.kind) {
-            let stmt_before_term = (block_data.statements.len() > 0)
-                .then_with(|| &block_data.statements[block_data.statements.len() - 1].kind);
-
-            if let Some(StatementKind::Assign(box (l, Rvalue::Discriminant(place)))) =
-                stmt_before_term
-            {
-                if l.as_local() == Some(local) {
-                    if let Some(r_local) = place.as_local() {
-                        let ty = body.local_decls[r_local].ty;
-
-                        if ty.is_enum() {
-                            blocks_to_update.push((bb, ty));
+            // The `Discriminant` read that defines `local` is frequently not the statement
+            // immediately before the switch: storage markers, debug-info nops, and unrelated
+            // assignments can sit in between. Scan backwards for it, skipping those, and bail
+            // out if anything on the way could reassign the locals we rely on.
+            let mut clobbered: Vec<Local> = Vec::new();
+
+            for stmt in block_data.statements.iter().rev() {
+                match &stmt.kind {
+                    StatementKind::Assign(box (l, Rvalue::Discriminant(place)))
+                        if l.as_local() == Some(local) =>
+                    {
+                        // The enum place must not have been overwritten between the read and
+                        // the switch, otherwise the switched-on value isn't this discriminant.
+                        if let Some(r_local) = place.as_local() {
+                            if !clobbered.contains(&r_local) {
+                                let ty = body.local_decls[r_local].ty;
+
+                                if ty.is_enum() {
+                                    blocks_to_update.push((bb, ty));
+                                }
+                            }
                         }
+                        break;
                     }
+                    // These never write to the locals we care about.
+                    StatementKind::StorageLive(_)
+                    | StatementKind::StorageDead(_)
+                    | StatementKind::Nop => {}
+                    // An assignment to `local` below the read feeds a different value into the
+                    // switch, so give up; an assignment to any other plain local is remembered
+                    // so we can tell whether it clobbered the enum place once we find the read.
+                    // An assignment through a projection also gives up.
+                    StatementKind::Assign(box (l, _)) => match l.as_local() {
+                        Some(w) if w == local => break,
+                        Some(w) => clobbered.push(w),
+                        None => break,
+                    },
+                    // Anything else could have arbitrary effects; be conservative and bail.
+                    _ => break,
                 }
             }
         }
@@ -74,9 +111,13 @@ impl<'tcx> MirPass<'tcx> for UninhabitedEnumBranching {
 
         let blocks_to_update = find_eligible_blocks(&body);
 
+        // Lazily created `Unreachable` block which all dead edges are routed to, so that
+        // codegen/the optimizer can learn those paths are dead and prune the blocks they
+        // used to lead to.
+        let mut unreachable_block = None;
+
         for (bb, discriminant_ty) in blocks_to_update {
             trace!("processing block {:?}", bb);
-            let block_data = &mut body[bb];
 
             let layout = tcx.layout_of(tcx.param_env(source.def_id()).and(discriminant_ty));
 
@@ -88,32 +129,82 @@ impl<'tcx> MirPass<'tcx> for UninhabitedEnumBranching {
 
             trace!("allowed_variants = {:?}", allowed_variants);
 
-            if let TerminatorKind::SwitchInt { values, targets, .. } =
-                &mut block_data.terminator_mut().kind
-            {
-                let vals = &*values;
-                let zipped = vals.iter().zip(targets.into_iter());
-
-                let mut matched_values = Vec::with_capacity(allowed_variants.len());
-                let mut matched_targets = Vec::with_capacity(allowed_variants.len() + 1);
-
-                for (val, target) in zipped {
-                    if allowed_variants.contains(val) {
-                        matched_values.push(*val);
-                        matched_targets.push(*target);
-                    } else {
-                        trace!("eliminating {:?} -> {:?}", val, target);
+            let source_info = body[bb].terminator().source_info;
+
+            let (discr, switch_ty, values, targets) =
+                match &body[bb].terminator().kind {
+                    TerminatorKind::SwitchInt { discr, switch_ty, values, targets } => {
+                        (discr.clone(), *switch_ty, values.clone(), targets.clone())
                     }
+                    _ => unreachable!(),
+                };
+
+            // Reading the discriminant of an entirely uninhabited enum is itself unreachable,
+            // so the whole block can simply terminate in `Unreachable`.
+            if allowed_variants.is_empty() {
+                body[bb].terminator_mut().kind = TerminatorKind::Unreachable;
+                continue;
+            }
+
+            let otherwise = *targets.last().unwrap();
+
+            // Partition the explicit arms into those reading an inhabited variant (kept) and
+            // those reading an uninhabited one (dead, but rerouted to `Unreachable`).
+            let mut inhabited = Vec::with_capacity(allowed_variants.len());
+            let mut eliminated = Vec::new();
+            for (val, &target) in values.iter().zip(targets.iter()) {
+                if allowed_variants.contains(val) {
+                    inhabited.push((*val, target));
+                } else {
+                    trace!("eliminating {:?} -> {:?}", val, target);
+                    eliminated.push(*val);
                 }
+            }
 
-                // handle the "otherwise" branch
-                matched_targets.push(targets.pop().unwrap());
+            // The kept arms fully enumerate the inhabited variants exactly when every allowed
+            // variant has a matching switch value; in that case the `otherwise` edge can only
+            // be reached through an uninhabited variant and is therefore dead.
+            let otherwise_unreachable = inhabited.len() == allowed_variants.len();
 
-                *values = matched_values.into();
-                *targets = matched_targets;
-            } else {
-                unreachable!()
+            // With a single live target and a dead `otherwise`, the switch degenerates to a
+            // `Goto` and every dead arm drops out of the CFG entirely.
+            if otherwise_unreachable && inhabited.len() == 1 {
+                body[bb].terminator_mut().kind = TerminatorKind::Goto { target: inhabited[0].1 };
+                continue;
+            }
+
+            let unreachable_block = *unreachable_block.get_or_insert_with(|| {
+                body.basic_blocks_mut().push(BasicBlockData {
+                    statements: Vec::new(),
+                    terminator: Some(Terminator {
+                        source_info,
+                        kind: TerminatorKind::Unreachable,
+                    }),
+                    is_cleanup: false,
+                })
+            });
+
+            let mut new_values = Vec::with_capacity(inhabited.len() + eliminated.len());
+            let mut new_targets = Vec::with_capacity(inhabited.len() + eliminated.len() + 1);
+
+            for (val, target) in inhabited {
+                new_values.push(val);
+                new_targets.push(target);
+            }
+            // Keep the dead arms as edges so their old successors become orphaned, but point
+            // them at the `Unreachable` block.
+            for val in eliminated {
+                new_values.push(val);
+                new_targets.push(unreachable_block);
             }
+            new_targets.push(if otherwise_unreachable { unreachable_block } else { otherwise });
+
+            body[bb].terminator_mut().kind = TerminatorKind::SwitchInt {
+                discr,
+                switch_ty,
+                values: new_values.into(),
+                targets: new_targets,
+            };
         }
     }
 }
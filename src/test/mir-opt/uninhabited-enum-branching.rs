@@ -0,0 +1,62 @@
+enum Empty {}
+
+// A two-variant enum with one uninhabited variant: the switch collapses to a `Goto` to its
+// single live target.
+enum Two {
+    A(Empty),
+    B,
+}
+
+// A three-variant enum with one uninhabited variant: the switch is kept, but the dead arm is
+// rerouted to the synthesized `Unreachable` block instead of being dropped.
+enum Three {
+    C(Empty),
+    D,
+    E,
+}
+
+fn two(x: Two) -> u32 {
+    match x {
+        Two::A(_) => 0,
+        Two::B => 1,
+    }
+}
+
+fn three(x: Three) -> u32 {
+    match x {
+        Three::C(_) => 0,
+        Three::D => 1,
+        Three::E => 2,
+    }
+}
+
+fn main() {
+    two(Two::B);
+    three(Three::D);
+}
+
+// END RUST SOURCE
+//
+// The discriminant of `Two` can only ever be `B`, so after the pass the `SwitchInt` degenerates
+// into an unconditional `Goto` to the `B` arm and the `A` arm drops out of the CFG.
+//
+// START rustc.two.UninhabitedEnumBranching.after.mir
+//      bb0: {
+//          _2 = discriminant(_1);
+//          goto -> bb2;
+//      }
+// END rustc.two.UninhabitedEnumBranching.after.mir
+//
+// `Three` still has two inhabited variants, so the `SwitchInt` is kept; the dead `C` arm is
+// pointed at the new `Unreachable` block (here `bb5`) rather than its original successor, and
+// the now-dead `otherwise` edge is routed there as well.
+//
+// START rustc.three.UninhabitedEnumBranching.after.mir
+//      bb0: {
+//          _2 = discriminant(_1);
+//          switchInt(move _2) -> [1isize: bb3, 2isize: bb4, 0isize: bb5, otherwise: bb5];
+//      }
+//      bb5: {
+//          unreachable;
+//      }
+// END rustc.three.UninhabitedEnumBranching.after.mir